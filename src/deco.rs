@@ -0,0 +1,226 @@
+use crate::rock_bottom::{atmospheres, Diver};
+
+const WATER_VAPOR_PRESSURE: f64 = 0.0627;
+const SURFACE_PRESSURE: f64 = 1.0;
+const AIR_F_N2: f64 = 0.79;
+const STOP_INCREMENT_M: f64 = 3.0;
+
+/// Bühlmann ZHL-16C half-time and M-value coefficients for a single tissue compartment.
+#[derive(Clone, Copy)]
+struct BuhlmannCoefficients {
+    half_time_min: f64,
+    a: f64,
+    b: f64,
+}
+
+// ZHL-16C nitrogen coefficients, compartments 1-16.
+const ZHL_16C_N2: [BuhlmannCoefficients; 16] = [
+    BuhlmannCoefficients { half_time_min: 5.0, a: 1.2599, b: 0.5050 },
+    BuhlmannCoefficients { half_time_min: 8.0, a: 1.1696, b: 0.5578 },
+    BuhlmannCoefficients { half_time_min: 12.5, a: 1.0000, b: 0.6514 },
+    BuhlmannCoefficients { half_time_min: 18.5, a: 0.8618, b: 0.7222 },
+    BuhlmannCoefficients { half_time_min: 27.0, a: 0.7562, b: 0.7825 },
+    BuhlmannCoefficients { half_time_min: 38.3, a: 0.6200, b: 0.8126 },
+    BuhlmannCoefficients { half_time_min: 54.3, a: 0.5043, b: 0.8434 },
+    BuhlmannCoefficients { half_time_min: 77.0, a: 0.4410, b: 0.8693 },
+    BuhlmannCoefficients { half_time_min: 109.0, a: 0.4000, b: 0.8910 },
+    BuhlmannCoefficients { half_time_min: 146.0, a: 0.3750, b: 0.9092 },
+    BuhlmannCoefficients { half_time_min: 187.0, a: 0.3500, b: 0.9222 },
+    BuhlmannCoefficients { half_time_min: 239.0, a: 0.3295, b: 0.9319 },
+    BuhlmannCoefficients { half_time_min: 305.0, a: 0.3065, b: 0.9403 },
+    BuhlmannCoefficients { half_time_min: 390.0, a: 0.2835, b: 0.9477 },
+    BuhlmannCoefficients { half_time_min: 498.0, a: 0.2610, b: 0.9544 },
+    BuhlmannCoefficients { half_time_min: 635.0, a: 0.2480, b: 0.9602 },
+];
+
+/// A single ZHL-16C tissue compartment tracking its current nitrogen loading.
+#[derive(Clone, Copy)]
+pub(crate) struct Compartment {
+    coefficients: BuhlmannCoefficients,
+    p_n2: f64,
+}
+
+impl Compartment {
+    fn at_surface_equilibrium() -> [Compartment; 16] {
+        let p_n2 = (SURFACE_PRESSURE - WATER_VAPOR_PRESSURE) * AIR_F_N2;
+        ZHL_16C_N2.map(|coefficients| Compartment { coefficients, p_n2 })
+    }
+
+    fn k_per_min(&self) -> f64 {
+        2f64.ln() / self.coefficients.half_time_min
+    }
+
+    /// Haldane uptake/off-gassing for a segment held at a constant ambient pressure.
+    fn load(&self, p_ambient: f64, f_n2: f64, minutes: f64) -> Compartment {
+        let p_insp = (p_ambient - WATER_VAPOR_PRESSURE) * f_n2;
+        let p_n2 = self.p_n2 + (p_insp - self.p_n2) * (1.0 - (-self.k_per_min() * minutes).exp());
+        Compartment { p_n2, ..*self }
+    }
+
+    /// Ambient pressure ceiling for this compartment's loading, using gradient factor `gf`
+    /// (1.0 recovers the raw Bühlmann ceiling `(P_comp - a) * b`).
+    fn ceiling_pressure(&self, gf: f64) -> f64 {
+        let BuhlmannCoefficients { a, b, .. } = self.coefficients;
+        (self.p_n2 - gf * a) / (1.0 - gf + gf / b)
+    }
+
+    fn ceiling_m(&self, gf: f64) -> f64 {
+        ((self.ceiling_pressure(gf) - SURFACE_PRESSURE) * 10.0).max(0.0)
+    }
+}
+
+/// A Bühlmann ZHL-16C decompression model with low/high gradient factors.
+pub(crate) struct DecoModel {
+    gf_low: f64,
+    gf_high: f64,
+}
+
+/// Result of evaluating a bottom exposure against the model.
+pub(crate) struct DecoResult {
+    pub(crate) controlling_compartment: usize,
+    pub(crate) ceiling_m: f64,
+    pub(crate) within_ndl: bool,
+}
+
+impl DecoModel {
+    pub(crate) fn new(gf_low: f64, gf_high: f64) -> DecoModel {
+        DecoModel { gf_low, gf_high }
+    }
+
+    /// Gradient factor to apply at `depth_m`, interpolated linearly between `gf_low` at the
+    /// first stop (`first_stop_depth_m`) and `gf_high` at the surface.
+    pub(crate) fn gf_at_depth(&self, depth_m: f64, first_stop_depth_m: f64) -> f64 {
+        if first_stop_depth_m <= 0.0 {
+            self.gf_high
+        } else {
+            let fraction = (depth_m / first_stop_depth_m).clamp(0.0, 1.0);
+            self.gf_high + (self.gf_low - self.gf_high) * fraction
+        }
+    }
+
+    /// Loads all 16 compartments through an ordered sequence of constant-pressure exposures
+    /// (`p_ambient`, `minutes`) starting from surface equilibrium — e.g. a multi-level
+    /// profile's segments, each approximated by its average ambient pressure.
+    fn load_profile(diver: &Diver, exposures: &[(f64, f64)]) -> [Compartment; 16] {
+        let f_n2 = diver.kit.tanks.first().map(|t| t.f_n2).unwrap_or(AIR_F_N2);
+        exposures.iter().fold(Compartment::at_surface_equilibrium(), |loaded, &(p_ambient, minutes)| {
+            loaded.map(|c| c.load(p_ambient, f_n2, minutes))
+        })
+    }
+
+    fn controlling(loaded: &[Compartment; 16], gf: f64) -> (usize, f64) {
+        loaded
+            .iter()
+            .map(|c| c.ceiling_m(gf))
+            .enumerate()
+            .fold((0, f64::MIN), |best, cur| if cur.1 > best.1 { cur } else { best })
+    }
+
+    /// Loads all 16 compartments through `exposures` and reports the controlling compartment,
+    /// its decompression ceiling (using `gf_low`, the gate for the first stop), and whether
+    /// the profile stays within no-decompression limits.
+    pub(crate) fn plan_profile(&self, diver: &Diver, exposures: &[(f64, f64)]) -> DecoResult {
+        let loaded = Self::load_profile(diver, exposures);
+        let (controlling_compartment, ceiling_m) = Self::controlling(&loaded, self.gf_low);
+
+        DecoResult {
+            controlling_compartment,
+            ceiling_m,
+            within_ndl: ceiling_m <= 0.0,
+        }
+    }
+
+    /// `plan_profile` for a single square bottom exposure held at a constant `depth_m`.
+    pub(crate) fn plan(&self, diver: &Diver, depth_m: f64, bottom_minutes: f64) -> DecoResult {
+        self.plan_profile(diver, &[(atmospheres(depth_m), bottom_minutes)])
+    }
+
+    /// The controlling compartment's ceiling for the same exposures as `plan_profile`, but
+    /// evaluated at an arbitrary gradient factor rather than `gf_low`. Callers walking a
+    /// profile's stop ladder shallower than the first stop use this together with
+    /// `gf_at_depth` to check whether a candidate stop depth is deep enough for its own
+    /// interpolated gradient factor.
+    pub(crate) fn ceiling_at_gf(&self, diver: &Diver, exposures: &[(f64, f64)], gf: f64) -> f64 {
+        let loaded = Self::load_profile(diver, exposures);
+        Self::controlling(&loaded, gf).1
+    }
+
+    /// Rounds a raw ceiling up to the nearest stop increment (3 m) — the depth a diver would
+    /// actually stop at, rather than the exact ambient pressure ceiling.
+    pub(crate) fn round_up_to_stop(ceiling_m: f64) -> f64 {
+        if ceiling_m <= 0.0 {
+            0.0
+        } else {
+            (ceiling_m / STOP_INCREMENT_M).ceil() * STOP_INCREMENT_M
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deco::DecoModel;
+    use crate::rock_bottom::{atmospheres, Diver, Kit, Tank, DiveMode};
+
+    fn air_diver() -> Diver {
+        Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit {
+                tanks: vec![Tank {
+                    service_pressure: 3442,
+                    capacity_cuft: 101.3,
+                    gauge_pressure: 3000.0,
+                    f_o2: 0.21,
+                    f_n2: 0.79,
+                    f_he: 0.0,
+                }],
+            },
+            mode: DiveMode::OpenCircuit,
+        }
+    }
+
+    #[test]
+    fn test_short_exposure_stays_within_ndl() {
+        let model = DecoModel::new(0.8, 0.9);
+        let result = model.plan(&air_diver(), 30.0, 10.0);
+        assert_eq!(result.controlling_compartment, 0);
+        assert_float_relative_eq!(result.ceiling_m, 0.0, 0.001);
+        assert_eq!(result.within_ndl, true);
+    }
+
+    #[test]
+    fn test_longer_exposure_requires_a_stop() {
+        let model = DecoModel::new(0.8, 0.9);
+        let result = model.plan(&air_diver(), 30.0, 20.0);
+        assert_eq!(result.controlling_compartment, 0);
+        assert_float_relative_eq!(result.ceiling_m, 0.9543, 0.001);
+        assert_eq!(result.within_ndl, false);
+    }
+
+    #[test]
+    fn test_gf_at_depth_interpolates_between_low_and_high() {
+        let model = DecoModel::new(0.8, 0.9);
+        assert_float_relative_eq!(model.gf_at_depth(3.0, 3.0), 0.8, 0.001);
+        assert_float_relative_eq!(model.gf_at_depth(0.0, 3.0), 0.9, 0.001);
+        assert_float_relative_eq!(model.gf_at_depth(1.5, 3.0), 0.85, 0.001);
+    }
+
+    #[test]
+    fn test_round_up_to_stop() {
+        assert_float_relative_eq!(DecoModel::round_up_to_stop(0.0), 0.0, 0.001);
+        assert_float_relative_eq!(DecoModel::round_up_to_stop(0.9543), 3.0, 0.001);
+        assert_float_relative_eq!(DecoModel::round_up_to_stop(3.0), 3.0, 0.001);
+        assert_float_relative_eq!(DecoModel::round_up_to_stop(3.1), 6.0, 0.001);
+    }
+
+    #[test]
+    fn test_ceiling_at_gf_relaxes_toward_gf_high() {
+        let model = DecoModel::new(0.8, 0.9);
+        let diver = air_diver();
+        let exposures = [(atmospheres(30.0), 20.0)];
+        let first_stop_gf = model.gf_at_depth(3.0, 3.0);
+        let surface_gf = model.gf_at_depth(0.0, 3.0);
+        assert_float_relative_eq!(model.ceiling_at_gf(&diver, &exposures, first_stop_gf), 0.9543, 0.001);
+        assert_float_relative_eq!(model.ceiling_at_gf(&diver, &exposures, surface_gf), 0.0, 0.001);
+    }
+}
@@ -0,0 +1,272 @@
+use std::vec;
+
+use crate::deco::{DecoModel, DecoResult};
+use crate::rock_bottom::{atmospheres, Diver, DiveMode, Tank, CCR_METABOLIC_RATE_LPM, LITERS_PER_CUFT};
+
+/// The role a `DiveSegment` plays in a profile.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) enum SegmentType {
+    Descent,
+    Bottom,
+    Ascent,
+    DecoStop,
+    SafetyStop,
+}
+
+/// One leg of a multi-level dive profile: depth moves linearly from `start_depth_m` to
+/// `end_depth_m` over `minutes`.
+#[derive(Clone)]
+pub(crate) struct DiveSegment {
+    pub(crate) segment_type: SegmentType,
+    pub(crate) start_depth_m: f64,
+    pub(crate) end_depth_m: f64,
+    pub(crate) minutes: f64,
+}
+
+impl DiveSegment {
+    fn average_ata(&self) -> f64 {
+        atmospheres((self.start_depth_m + self.end_depth_m) / 2.0)
+    }
+
+    fn open_circuit_gas_cuft(&self, rmv: f64) -> f64 {
+        rmv * self.average_ata() * self.minutes
+    }
+
+    /// CCR gas for this segment: metabolic O2 for the whole segment, plus diluent to fill the
+    /// loop on descent only (level and ascending segments don't need topping up).
+    fn closed_circuit_gas_cuft(&self, loop_volume_l: f64) -> f64 {
+        let o2_liters = CCR_METABOLIC_RATE_LPM * self.minutes;
+        let diluent_liters = match self.segment_type {
+            SegmentType::Descent => {
+                loop_volume_l * (atmospheres(self.end_depth_m) - atmospheres(self.start_depth_m)).max(0.0)
+            }
+            _ => 0.0,
+        };
+        (o2_liters + diluent_liters) / LITERS_PER_CUFT
+    }
+
+    fn gas_volume_cuft(&self, diver: &Diver) -> f64 {
+        match diver.mode {
+            DiveMode::OpenCircuit => self.open_circuit_gas_cuft(diver.rmv),
+            DiveMode::ClosedCircuit { loop_volume_l, .. } => self.closed_circuit_gas_cuft(loop_volume_l),
+        }
+    }
+}
+
+/// Gas used by a single segment of a `DivePlan`.
+pub(crate) struct SegmentConsumption {
+    pub(crate) segment_type: SegmentType,
+    pub(crate) gas_cuft: f64,
+}
+
+/// Per-segment and total gas consumption for a `DivePlan`.
+pub(crate) struct PlanConsumption {
+    pub(crate) segments: vec::Vec<SegmentConsumption>,
+    pub(crate) total_cuft: f64,
+}
+
+/// A multi-level dive profile: an ordered sequence of `DiveSegment`s flown by a `Diver`
+/// with their kit, rather than a single square bottom depth and baked-in ascent assumptions.
+pub(crate) struct DivePlan {
+    pub(crate) diver: Diver,
+    pub(crate) segments: vec::Vec<DiveSegment>,
+}
+
+impl DivePlan {
+    /// Walks the segments in order, returning per-segment and total gas consumption in cuft.
+    pub(crate) fn gas_consumption(&self) -> PlanConsumption {
+        let segments = self
+            .segments
+            .iter()
+            .map(|s| SegmentConsumption {
+                segment_type: s.segment_type,
+                gas_cuft: s.gas_volume_cuft(&self.diver),
+            })
+            .collect::<vec::Vec<SegmentConsumption>>();
+
+        let total_cuft = segments.iter().map(|s| s.gas_cuft).sum();
+
+        PlanConsumption { segments, total_cuft }
+    }
+
+    /// The deepest depth reached by any segment — the rock-bottom reserve calculation's
+    /// single-depth input.
+    fn deepest_depth_m(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|s| s.start_depth_m.max(s.end_depth_m))
+            .fold(0.0, f64::max)
+    }
+
+    /// The leading `Descent`/`Bottom` segments of the profile, as (ambient pressure, minutes)
+    /// exposures — the loading the deco model evaluates to find the first required stop,
+    /// before any ascent or stop segments start off-gassing the diver.
+    fn bottom_exposures(&self) -> Vec<(f64, f64)> {
+        self.segments
+            .iter()
+            .take_while(|s| matches!(s.segment_type, SegmentType::Descent | SegmentType::Bottom))
+            .map(|s| (s.average_ata(), s.minutes))
+            .collect()
+    }
+
+    /// Rock-bottom reserve for this profile's deepest point, using the same allocation the
+    /// single-depth planner uses.
+    pub(crate) fn rock_bottom_reserve(&self) -> Result<Vec<Tank>, &'static str> {
+        self.diver.rock_bottom_pressure_rec(self.deepest_depth_m())
+    }
+
+    /// Controlling compartment, ceiling, and NDL status at the end of this profile's
+    /// descent and bottom time.
+    pub(crate) fn deco_status(&self, deco_model: &DecoModel) -> DecoResult {
+        deco_model.plan_profile(&self.diver, &self.bottom_exposures())
+    }
+
+    /// Walks the full profile in order, checking every `DecoStop` segment against the
+    /// ceiling required for its own gradient factor (interpolated between `gf_low` at the
+    /// first stop and `gf_high` at the surface) given the loading actually accumulated by
+    /// the segments flown so far — rather than trusting the authored stop depths blindly.
+    pub(crate) fn validate_deco_stops(&self, deco_model: &DecoModel) -> Result<(), &'static str> {
+        let status = deco_model.plan_profile(&self.diver, &self.bottom_exposures());
+        if status.within_ndl {
+            return Ok(());
+        }
+        let first_stop_depth_m = DecoModel::round_up_to_stop(status.ceiling_m);
+
+        let mut exposures: Vec<(f64, f64)> = Vec::new();
+        for segment in &self.segments {
+            if segment.segment_type == SegmentType::DecoStop {
+                let gf = deco_model.gf_at_depth(segment.end_depth_m, first_stop_depth_m);
+                let required_ceiling_m = deco_model.ceiling_at_gf(&self.diver, &exposures, gf);
+                if segment.end_depth_m < required_ceiling_m {
+                    return Err("Deco stop is shallower than the required ceiling");
+                }
+            }
+            exposures.push((segment.average_ata(), segment.minutes));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::deco::DecoModel;
+    use crate::dive_plan::{DivePlan, DiveSegment, SegmentType};
+    use crate::rock_bottom::{Diver, Kit, Tank, DiveMode};
+
+    fn oc_diver() -> Diver {
+        Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit {
+                tanks: vec![Tank {
+                    service_pressure: 3442,
+                    capacity_cuft: 101.3,
+                    gauge_pressure: 3000.0,
+                    f_o2: 0.21,
+                    f_n2: 0.79,
+                    f_he: 0.0,
+                }],
+            },
+            mode: DiveMode::OpenCircuit,
+        }
+    }
+
+    fn square_30m_segments() -> Vec<DiveSegment> {
+        vec![
+            DiveSegment { segment_type: SegmentType::Descent, start_depth_m: 0.0, end_depth_m: 30.0, minutes: 3.0 },
+            DiveSegment { segment_type: SegmentType::Bottom, start_depth_m: 30.0, end_depth_m: 30.0, minutes: 20.0 },
+            DiveSegment { segment_type: SegmentType::Ascent, start_depth_m: 30.0, end_depth_m: 0.0, minutes: 10.0 },
+        ]
+    }
+
+    #[test]
+    fn test_open_circuit_gas_consumption() {
+        let plan = DivePlan { diver: oc_diver(), segments: square_30m_segments() };
+        let consumption = plan.gas_consumption();
+        assert_eq!(consumption.segments.len(), 3);
+        assert_float_relative_eq!(consumption.segments[0].gas_cuft, 5.25, 0.001);
+        assert_float_relative_eq!(consumption.segments[1].gas_cuft, 56.0, 0.001);
+        assert_float_relative_eq!(consumption.segments[2].gas_cuft, 17.5, 0.001);
+        assert_float_relative_eq!(consumption.total_cuft, 78.75, 0.001);
+    }
+
+    #[test]
+    fn test_closed_circuit_gas_consumption() {
+        let mut diver = oc_diver();
+        diver.mode = DiveMode::ClosedCircuit { setpoint_ppo2: 1.3, loop_volume_l: 6.0 };
+        let plan = DivePlan { diver, segments: square_30m_segments() };
+        let consumption = plan.gas_consumption();
+        assert_float_relative_eq!(consumption.segments[0].gas_cuft, 0.7204, 0.001);
+        assert_float_relative_eq!(consumption.segments[1].gas_cuft, 0.5651, 0.001);
+        assert_float_relative_eq!(consumption.segments[2].gas_cuft, 0.2825, 0.001);
+        assert_float_relative_eq!(consumption.total_cuft, 1.568, 0.01);
+    }
+
+    #[test]
+    fn test_rock_bottom_reserve_uses_deepest_segment() {
+        let plan = DivePlan { diver: oc_diver(), segments: square_30m_segments() };
+        let tanks = plan.rock_bottom_reserve().expect("rock bottom shouldn't fail with a valid kit");
+        assert_eq!(tanks.len(), 1);
+        let deepest = oc_diver().rock_bottom_pressure_rec(30.0).expect("rock bottom shouldn't fail with a valid kit");
+        assert_float_relative_eq!(tanks[0].gauge_pressure, deepest[0].gauge_pressure, 0.001);
+    }
+
+    #[test]
+    fn test_deco_status_feeds_from_bottom_segment() {
+        let plan = DivePlan { diver: oc_diver(), segments: square_30m_segments() };
+        let model = DecoModel::new(0.8, 0.9);
+        let status = plan.deco_status(&model);
+        assert_eq!(status.within_ndl, false);
+        assert_float_relative_eq!(status.ceiling_m, 1.1234, 0.001);
+    }
+
+    /// A deep enough, long enough profile that a single direct ascent to the surface still
+    /// leaves a real ceiling under `gf_high` — so skipping the required stop is actually
+    /// detectable, unlike a shallow square profile where a brief ascent off-gasses it away.
+    fn deep_descent_and_bottom() -> Vec<DiveSegment> {
+        vec![
+            DiveSegment { segment_type: SegmentType::Descent, start_depth_m: 0.0, end_depth_m: 40.0, minutes: 4.0 },
+            DiveSegment { segment_type: SegmentType::Bottom, start_depth_m: 40.0, end_depth_m: 40.0, minutes: 30.0 },
+        ]
+    }
+
+    #[test]
+    fn test_validate_deco_stops_accepts_a_deep_enough_stop() {
+        let mut segments = deep_descent_and_bottom();
+        segments.push(DiveSegment {
+            segment_type: SegmentType::Ascent,
+            start_depth_m: 40.0,
+            end_depth_m: 9.0,
+            minutes: 4.0,
+        });
+        segments.push(DiveSegment {
+            segment_type: SegmentType::DecoStop,
+            start_depth_m: 9.0,
+            end_depth_m: 9.0,
+            minutes: 5.0,
+        });
+        let plan = DivePlan { diver: oc_diver(), segments };
+        let model = DecoModel::new(0.8, 0.9);
+        assert_eq!(plan.validate_deco_stops(&model), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_deco_stops_rejects_a_stop_that_is_too_shallow() {
+        let mut segments = deep_descent_and_bottom();
+        segments.push(DiveSegment {
+            segment_type: SegmentType::Ascent,
+            start_depth_m: 40.0,
+            end_depth_m: 0.0,
+            minutes: 10.0,
+        });
+        segments.push(DiveSegment {
+            segment_type: SegmentType::DecoStop,
+            start_depth_m: 0.0,
+            end_depth_m: 0.0,
+            minutes: 5.0,
+        });
+        let plan = DivePlan { diver: oc_diver(), segments };
+        let model = DecoModel::new(0.8, 0.9);
+        assert!(plan.validate_deco_stops(&model).is_err());
+    }
+}
@@ -0,0 +1,6 @@
+#[macro_use]
+extern crate assert_float_eq;
+
+pub mod rock_bottom;
+pub mod deco;
+pub mod dive_plan;
@@ -1,30 +1,49 @@
 use std::convert::TryFrom;
 use std::vec;
 use std::string::String;
-use conv::prelude::ValueFrom;
-use conv;
 
 const SAFETY_STOP_DEPTH: f64 = 4.5;
 const ASCENT_RATE: f64 = 9.14;
 const SAFETY_STOP_MINUTES: f64 = 3.0;
 
+const DEFAULT_PO2_MAX: f64 = 1.4;
+const DEFAULT_MAX_END_M: f64 = 30.0;
+const MAX_GAS_DENSITY_G_L: f64 = 6.2;
+
+const O2_DENSITY_G_L: f64 = 1.429;
+const N2_DENSITY_G_L: f64 = 1.251;
+const HE_DENSITY_G_L: f64 = 0.179;
+
+pub(crate) const LITERS_PER_CUFT: f64 = 28.3168;
+// Metabolic O2 consumption on a CCR loop, independent of depth.
+pub(crate) const CCR_METABOLIC_RATE_LPM: f64 = 0.8;
+
+/// How a `Diver` draws down their kit's gas supply.
+#[derive(Clone, Copy)]
+pub(crate) enum DiveMode {
+    OpenCircuit,
+    ClosedCircuit { setpoint_ppo2: f64, loop_volume_l: f64 },
+}
+
 #[derive(Clone)]
-struct Tank {
-    service_pressure: u16,
-    capacity_cuft: f64,
-    gauge_pressure: f64,
-    f_o2: f64,
-    f_n2: f64,
+pub(crate) struct Tank {
+    pub(crate) service_pressure: u16,
+    pub(crate) capacity_cuft: f64,
+    pub(crate) gauge_pressure: f64,
+    pub(crate) f_o2: f64,
+    pub(crate) f_n2: f64,
+    pub(crate) f_he: f64,
 }
 
-struct Diver {
-    name: String,
-    rmv: f64,
-    kit: Kit,
+pub(crate) struct Diver {
+    pub(crate) name: String,
+    pub(crate) rmv: f64,
+    pub(crate) kit: Kit,
+    pub(crate) mode: DiveMode,
 }
 
-struct Kit {
-    tanks: vec::Vec<Tank>
+pub(crate) struct Kit {
+    pub(crate) tanks: vec::Vec<Tank>
 }
 
 impl Tank {
@@ -45,85 +64,219 @@ impl Tank {
     }
 
     fn breathable_at(&self, depth_m: f64) -> bool {
-        self.pO2(depth_m) <= 1.4
+        self.pO2(depth_m) <= DEFAULT_PO2_MAX
     }
 
-    fn with_volume(self, volume: f64) -> Option<Tank> {
-        self.tank_factor().and_then(|tank_factor| {
-            Some(Tank{
-                service_pressure: self.service_pressure,
-                capacity_cuft: self.capacity_cuft,
-                f_o2: self.f_o2,
-                f_n2: self.f_n2,
-                gauge_pressure: volume / tank_factor
-            })
-        }) 
+    /// Maximum Operating Depth for this mix at a given pO2 ceiling.
+    fn mod_m(&self, po2_max: f64) -> f64 {
+        10.0 * (po2_max / self.f_o2 - 1.0)
+    }
+
+    /// Equivalent Narcotic Depth at `depth_m`, treating O2 as narcotic and helium as not.
+    fn end_m(&self, depth_m: f64) -> f64 {
+        (atmospheres(depth_m) * (1.0 - self.f_he) - 1.0) * 10.0
+    }
+
+    /// Gas density at `depth_m` in g/L.
+    fn density_g_l(&self, depth_m: f64) -> f64 {
+        atmospheres(depth_m) * (self.f_o2 * O2_DENSITY_G_L + self.f_n2 * N2_DENSITY_G_L + self.f_he * HE_DENSITY_G_L)
     }
 
-    fn add_volume(self, volume: f64) -> Option<Tank> {
+    /// Whether this mix is safe to breathe at `depth_m`: pO2 within `po2_max`, END within
+    /// `max_end_m`, and density within the ~6.2 g/L work-of-breathing limit.
+    fn gas_is_safe_at(&self, depth_m: f64, po2_max: f64, max_end_m: f64) -> bool {
+        self.pO2(depth_m) <= po2_max
+            && self.end_m(depth_m) <= max_end_m
+            && self.density_g_l(depth_m) <= MAX_GAS_DENSITY_G_L
+    }
+
+    /// `gas_is_safe_at` with the usual recreational/tech defaults: 1.4 pO2 max, 30 m END.
+    fn gas_is_safe_at_defaults(&self, depth_m: f64) -> bool {
+        self.gas_is_safe_at(depth_m, DEFAULT_PO2_MAX, DEFAULT_MAX_END_M)
+    }
+
+    /// Usable gas capacity in cuft: the volume the tank holds at full service pressure.
+    fn usable_capacity_cuft(&self) -> Option<f64> {
+        self.tank_factor().map(|tank_factor| tank_factor * self.service_pressure as f64)
+    }
+
+    /// Sets the tank's gauge pressure to hold `volume` cuft, clamped to `[0, service_pressure]`.
+    /// `unmet_cuft` reports how much of `volume` didn't fit because it would have pushed the
+    /// gauge pressure outside that range.
+    fn with_volume(self, volume: f64) -> Option<VolumeFill> {
+        self.tank_factor().map(|tank_factor| {
+            let max_volume = self.service_pressure as f64 * tank_factor;
+            let clamped_volume = volume.clamp(0.0, max_volume);
+            let tank = Tank {
+                gauge_pressure: clamped_volume / tank_factor,
+                ..self
+            };
+            VolumeFill { tank, unmet_cuft: volume - clamped_volume }
+        })
+    }
+
+    fn add_volume(self, volume: f64) -> Option<VolumeFill> {
         self.gas_volume_cuft().and_then(|gv| {
             self.with_volume(gv + volume)
         })
     }
 }
 
+/// Result of adding/setting volume on a `Tank`: the tank as filled, plus any volume that
+/// didn't fit because it would have exceeded `service_pressure` or dropped below zero.
+struct VolumeFill {
+    tank: Tank,
+    unmet_cuft: f64,
+}
+
+/// Result of allocating gas across a set of tanks: the tanks as filled, plus the total
+/// `needed_gas` that didn't fit because some tank(s) hit their service-pressure limit.
+struct GasAllocation {
+    tanks: Vec<Tank>,
+    shortfall_cuft: f64,
+}
+
+/// Reserve gas for a closed-circuit dive: the diluent tanks topped off for descent, plus the
+/// onboard O2 needed to cover the reserve ascent and safety stop. The O2 comes from a
+/// dedicated cylinder outside `Kit`, so it's reported alongside the diluent tanks rather than
+/// folded into them.
+pub(crate) struct ClosedCircuitReserve {
+    pub(crate) diluent_tanks: Vec<Tank>,
+    pub(crate) onboard_o2_liters: f64,
+}
+
 impl Diver {
-    fn rock_bottom_pressure_rec(&self, depth_m: f64) -> Result<Vec<Tank>, &'static str> {
+    pub(crate) fn rock_bottom_pressure_rec(&self, depth_m: f64) -> Result<Vec<Tank>, &'static str> {
+        match self.mode {
+            DiveMode::OpenCircuit => self.rock_bottom_open_circuit(depth_m),
+            DiveMode::ClosedCircuit { setpoint_ppo2, loop_volume_l } => {
+                self.rock_bottom_closed_circuit(depth_m, setpoint_ppo2, loop_volume_l)
+                    .map(|reserve| reserve.diluent_tanks)
+            }
+        }
+    }
+
+    fn rock_bottom_open_circuit(&self, depth_m: f64) -> Result<Vec<Tank>, &'static str> {
         let ascent_ata = atmospheres((depth_m - SAFETY_STOP_DEPTH) / 2.0);
         let bottom_ata = atmospheres(depth_m);
-        
+
         let ascent_minutes = depth_m / ASCENT_RATE;  // 30ft / min
         let problem_gas = self.rmv * 2.0 * bottom_ata * 4.0;
         let ascent_gas = ascent_ata * ascent_minutes * self.rmv * 2.0;
         let stop_gas = atmospheres(SAFETY_STOP_DEPTH) * SAFETY_STOP_MINUTES * self.rmv * 2.0;
-        
-        
+
+
         let bottom_tanks = self.kit.tanks
             .clone()
             .into_iter()
-            .filter(|t| t.breathable_at(depth_m))
+            .filter(|t| t.gas_is_safe_at_defaults(depth_m))
             .collect::<Vec<Tank>>();
         let mut stop_tanks = self.kit.tanks
             .clone()
             .into_iter()
             .filter(|t| {
-                t.breathable_at(SAFETY_STOP_DEPTH) && !t.breathable_at(depth_m)
+                t.gas_is_safe_at_defaults(SAFETY_STOP_DEPTH) && !t.gas_is_safe_at_defaults(depth_m)
             }).collect::<Vec<Tank>>();
-        let bottom_tanks = divide_gas_among(bottom_tanks, problem_gas + ascent_gas,  &mut Tank::with_volume)
-            .expect("Failed to allocate gas to tanks.");
-        let all_tanks = match bottom_tanks {
-            Some(mut bt) => {
-                bt.append(&mut stop_tanks);
-                Ok(bt)
-            },
-            None => Err("No valid bottom tanks")
-        }?;
-        match divide_gas_among(all_tanks, stop_gas, &mut Tank::add_volume).expect("OOF") {
-            Some(t) => Ok(t),
-            None => Err("BIG OOF.")
+        if bottom_tanks.is_empty() {
+            return Err("No valid bottom tanks");
         }
+        let mut all_tanks = divide_gas_among(bottom_tanks, problem_gas + ascent_gas, &mut Tank::with_volume)?.tanks;
+        all_tanks.append(&mut stop_tanks);
 
+        let final_allocation = divide_gas_among(all_tanks, stop_gas, &mut Tank::add_volume)?;
+        if final_allocation.shortfall_cuft > 0.0 {
+            return Err("Kit does not carry enough reserve gas for this profile.");
+        }
+        Ok(final_allocation.tanks)
     }
-}
 
-fn divide_gas_among<F>(tanks: Vec<Tank>, needed_gas: f64,  volume_allocator: &mut F) -> Result<Option<Vec<Tank>>, conv::PosOverflow<usize>>
-where F: FnMut(Tank, f64) -> Option<Tank> {
-    f64::value_from(tanks.len()).and_then(|tank_count| {
-        let gas_per_ascent_tank = needed_gas / tank_count;
-        Ok(tanks
+    /// CCR reserve: diluent is only drawn down to keep the loop full on descent, not scaled
+    /// by depth like open-circuit consumption. O2 is metabolic and supplied from a dedicated
+    /// onboard cylinder outside of `Kit`, sized by `ccr_o2_reserve_liters` and reported
+    /// alongside the diluent tanks in the returned `ClosedCircuitReserve`.
+    fn rock_bottom_closed_circuit(
+        &self,
+        depth_m: f64,
+        setpoint_ppo2: f64,
+        loop_volume_l: f64,
+    ) -> Result<ClosedCircuitReserve, &'static str> {
+        if setpoint_ppo2 > DEFAULT_PO2_MAX {
+            return Err("CCR setpoint exceeds the maximum safe pO2.");
+        }
+
+        let descent_ata = atmospheres(depth_m) - atmospheres(0.0);
+        let diluent_gas = (loop_volume_l * descent_ata) / LITERS_PER_CUFT;
+
+        let diluent_tanks = self.kit.tanks
+            .clone()
             .into_iter()
-            .map(|t| { volume_allocator(t, gas_per_ascent_tank)})
-            .collect::<Option<Vec<_>>>())
-    })
+            .filter(|t| t.gas_is_safe_at(depth_m, setpoint_ppo2, DEFAULT_MAX_END_M))
+            .collect::<Vec<Tank>>();
+        if diluent_tanks.is_empty() {
+            return Err("No valid diluent tanks");
+        }
+
+        let allocation = divide_gas_among(diluent_tanks, diluent_gas, &mut Tank::with_volume)?;
+        if allocation.shortfall_cuft > 0.0 {
+            return Err("Kit does not carry enough diluent for this profile.");
+        }
+        Ok(ClosedCircuitReserve {
+            diluent_tanks: allocation.tanks,
+            onboard_o2_liters: self.ccr_o2_reserve_liters(depth_m),
+        })
+    }
+
+    /// O2 required from the onboard CCR cylinder for `depth_m`'s reserve ascent + stop time.
+    fn ccr_o2_reserve_liters(&self, depth_m: f64) -> f64 {
+        let ascent_minutes = depth_m / ASCENT_RATE;
+        CCR_METABOLIC_RATE_LPM * (ascent_minutes + SAFETY_STOP_MINUTES)
+    }
+}
+
+/// Splits `needed_gas` across `tanks` in proportion to each tank's usable capacity, rather
+/// than evenly, so a small pony bottle isn't asked to carry the same share as a set of
+/// doubles. Reports any `needed_gas` that didn't fit because a tank hit its service-pressure
+/// limit, so callers can detect an under-equipped diver instead of getting a silently
+/// invalid `Tank`.
+fn divide_gas_among<F>(
+    tanks: Vec<Tank>,
+    needed_gas: f64,
+    volume_allocator: &mut F,
+) -> Result<GasAllocation, &'static str>
+where
+    F: FnMut(Tank, f64) -> Option<VolumeFill>,
+{
+    let capacities = tanks
+        .iter()
+        .map(|t| t.usable_capacity_cuft().ok_or("Tank has an invalid service pressure."))
+        .collect::<Result<Vec<f64>, _>>()?;
+    let total_capacity: f64 = capacities.iter().sum();
+    if total_capacity <= 0.0 {
+        return Err("No tank capacity available to allocate gas.");
+    }
+
+    let fills = tanks
+        .into_iter()
+        .zip(capacities)
+        .map(|(t, capacity)| {
+            let share = needed_gas * (capacity / total_capacity);
+            volume_allocator(t, share).ok_or("Failed to apply gas volume to a tank.")
+        })
+        .collect::<Result<Vec<VolumeFill>, _>>()?;
+
+    let shortfall_cuft = fills.iter().map(|f| f.unmet_cuft.max(0.0)).sum();
+    let tanks = fills.into_iter().map(|f| f.tank).collect();
+
+    Ok(GasAllocation { tanks, shortfall_cuft })
 }
 
-fn atmospheres(depth_m: f64) -> f64 {
+pub(crate) fn atmospheres(depth_m: f64) -> f64 {
     1.0 + depth_m / 10.0
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rock_bottom::{Tank, Diver, Kit};
+    use crate::rock_bottom::{Tank, Diver, Kit, DiveMode};
     
     #[test]
     fn test_tank_volume() {
@@ -133,6 +286,7 @@ mod tests {
             gauge_pressure: 750.0,
             f_o2: 0.21,
             f_n2: 0.79,
+            f_he: 0.0,
         };
         let vol = tank1.gas_volume_cuft();
         match vol {
@@ -149,11 +303,29 @@ mod tests {
             gauge_pressure: 3200.0,
             f_o2: 0.5,
             f_n2: 0.5,
+            f_he: 0.0,
         };
         assert_eq!(t50.breathable_at(19.0), false);
         assert_eq!(t50.breathable_at(18.0), true);
     }
 
+    #[test]
+    fn test_trimix_limits() {
+        let tmx = Tank {
+            service_pressure: 3442,
+            capacity_cuft: 101.3,
+            gauge_pressure: 3200.0,
+            f_o2: 0.18,
+            f_n2: 0.47,
+            f_he: 0.35,
+        };
+        assert_float_relative_eq!(tmx.mod_m(1.4), 67.78, 0.01);
+        assert_float_relative_eq!(tmx.end_m(45.0), 25.75, 0.01);
+        assert_eq!(tmx.gas_is_safe_at(45.0, 1.4, 30.0), true);
+        assert_eq!(tmx.gas_is_safe_at(90.0, 1.4, 30.0), false);
+        assert_eq!(tmx.gas_is_safe_at_defaults(45.0), true);
+    }
+
     #[test]
     fn test_rock_bottom() {
         let d = Diver {
@@ -166,8 +338,10 @@ mod tests {
                     gauge_pressure: 750.0,
                     f_o2: 0.21,
                     f_n2: 0.79,
+                    f_he: 0.0,
                 }]
-            }
+            },
+            mode: DiveMode::OpenCircuit,
         };
         let rb_tanks = d.rock_bottom_pressure_rec(30.0)
         .expect("Rock bottom shouldn't fail with a rec diving config");
@@ -175,4 +349,195 @@ mod tests {
         ()
     }
 
-} 
\ No newline at end of file
+    #[test]
+    fn test_rock_bottom_splits_proportionally_to_capacity() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![
+                    Tank{
+                        service_pressure: 3442,
+                        capacity_cuft: 101.3,
+                        gauge_pressure: 750.0,
+                        f_o2: 0.21,
+                        f_n2: 0.79,
+                        f_he: 0.0,
+                    },
+                    Tank{
+                        service_pressure: 3000,
+                        capacity_cuft: 13.0,
+                        gauge_pressure: 500.0,
+                        f_o2: 0.21,
+                        f_n2: 0.79,
+                        f_he: 0.0,
+                    },
+                ]
+            },
+            mode: DiveMode::OpenCircuit,
+        };
+        let rb_tanks = d.rock_bottom_pressure_rec(30.0)
+            .expect("Rock bottom shouldn't fail with a rec diving config");
+        assert_eq!(rb_tanks.len(), 2);
+        for t in &rb_tanks {
+            assert!(t.gauge_pressure <= t.service_pressure as f64);
+            assert!(t.gauge_pressure >= 0.0);
+        }
+        let volumes: Vec<f64> = rb_tanks.iter().map(|t| t.gas_volume_cuft().unwrap()).collect();
+        assert_float_relative_eq!(volumes[0] / volumes[1], 101.3 / 13.0, 0.01);
+    }
+
+    #[test]
+    fn test_rock_bottom_open_circuit_rejects_a_narcotic_dense_mix() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![Tank{
+                    service_pressure: 3442,
+                    capacity_cuft: 101.3,
+                    gauge_pressure: 3000.0,
+                    f_o2: 0.10,
+                    f_n2: 0.90,
+                    f_he: 0.0,
+                }]
+            },
+            mode: DiveMode::OpenCircuit,
+        };
+        match d.rock_bottom_pressure_rec(45.0) {
+            Err(msg) => assert_eq!(msg, "No valid bottom tanks"),
+            Ok(_) => panic!("a 10/90 nitrox mix at 45m with END/density past the defaults shouldn't pass the OC safety filter"),
+        }
+    }
+
+    #[test]
+    fn test_rock_bottom_splits_volume_proportionally_to_capacity() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![
+                    Tank{
+                        service_pressure: 3000,
+                        capacity_cuft: 80.0,
+                        gauge_pressure: 3000.0,
+                        f_o2: 0.21,
+                        f_n2: 0.79,
+                        f_he: 0.0,
+                    },
+                    Tank{
+                        service_pressure: 3000,
+                        capacity_cuft: 40.0,
+                        gauge_pressure: 3000.0,
+                        f_o2: 0.21,
+                        f_n2: 0.79,
+                        f_he: 0.0,
+                    },
+                ]
+            },
+            mode: DiveMode::OpenCircuit,
+        };
+        let rb_tanks = d.rock_bottom_pressure_rec(30.0)
+            .expect("well-equipped kit shouldn't fail");
+        assert_eq!(rb_tanks.len(), 2);
+        let volumes: Vec<f64> = rb_tanks.iter().map(|t| t.gas_volume_cuft().unwrap()).collect();
+        assert_float_relative_eq!(volumes[0] / volumes[1], 80.0 / 40.0, 0.01);
+    }
+
+    #[test]
+    fn test_rock_bottom_fails_when_kit_cannot_carry_enough_reserve() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![Tank{
+                    service_pressure: 200,
+                    capacity_cuft: 1.0,
+                    gauge_pressure: 200.0,
+                    f_o2: 0.21,
+                    f_n2: 0.79,
+                    f_he: 0.0,
+                }]
+            },
+            mode: DiveMode::OpenCircuit,
+        };
+        match d.rock_bottom_pressure_rec(30.0) {
+            Err(msg) => assert_eq!(msg, "Kit does not carry enough reserve gas for this profile."),
+            Ok(_) => panic!("a single 1 cuft bottle can't carry a 30m open-circuit reserve"),
+        }
+    }
+
+    #[test]
+    fn test_rock_bottom_closed_circuit() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![Tank{
+                    service_pressure: 3442,
+                    capacity_cuft: 101.3,
+                    gauge_pressure: 3000.0,
+                    f_o2: 0.18,
+                    f_n2: 0.82,
+                    f_he: 0.0,
+                }]
+            },
+            mode: DiveMode::ClosedCircuit { setpoint_ppo2: 1.3, loop_volume_l: 6.0 },
+        };
+        let reserve = d.rock_bottom_closed_circuit(30.0, 1.3, 6.0)
+            .expect("CCR rock bottom shouldn't fail with a valid diluent");
+        assert_eq!(reserve.diluent_tanks.len(), 1);
+        assert_float_relative_eq!(reserve.onboard_o2_liters, 5.0258, 0.001);
+
+        let rb_tanks = d.rock_bottom_pressure_rec(30.0)
+            .expect("CCR rock bottom shouldn't fail with a valid diluent");
+        assert_eq!(rb_tanks.len(), 1);
+    }
+
+    #[test]
+    fn test_rock_bottom_closed_circuit_rejects_an_unsafe_setpoint() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![Tank{
+                    service_pressure: 3442,
+                    capacity_cuft: 101.3,
+                    gauge_pressure: 3000.0,
+                    f_o2: 0.18,
+                    f_n2: 0.82,
+                    f_he: 0.0,
+                }]
+            },
+            mode: DiveMode::ClosedCircuit { setpoint_ppo2: 1.6, loop_volume_l: 6.0 },
+        };
+        match d.rock_bottom_pressure_rec(30.0) {
+            Err(msg) => assert_eq!(msg, "CCR setpoint exceeds the maximum safe pO2."),
+            Ok(_) => panic!("a 1.6 ppO2 setpoint exceeds the 1.4 default max"),
+        }
+    }
+
+    #[test]
+    fn test_rock_bottom_closed_circuit_fails_when_diluent_is_too_small() {
+        let d = Diver {
+            name: String::from("Tyler"),
+            rmv: 0.7,
+            kit: Kit{
+                tanks: vec![Tank{
+                    service_pressure: 200,
+                    capacity_cuft: 0.5,
+                    gauge_pressure: 200.0,
+                    f_o2: 0.21,
+                    f_n2: 0.79,
+                    f_he: 0.0,
+                }]
+            },
+            mode: DiveMode::ClosedCircuit { setpoint_ppo2: 1.3, loop_volume_l: 6.0 },
+        };
+        match d.rock_bottom_pressure_rec(30.0) {
+            Err(msg) => assert_eq!(msg, "Kit does not carry enough diluent for this profile."),
+            Ok(_) => panic!("a half-cuft diluent bottle can't top up a 6L loop to 30m"),
+        }
+    }
+
+}
\ No newline at end of file